@@ -0,0 +1,99 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+
+/// Which side of a device a captured buffer came from, used to label hex-dump lines.
+#[derive(Clone, Copy)]
+pub enum Direction {
+    Tx,
+    Rx,
+}
+
+impl Direction {
+    fn label(self) -> &'static str {
+        match self {
+            Direction::Tx => "TX",
+            Direction::Rx => "RX",
+        }
+    }
+}
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_LINKTYPE_USER0: u32 = 147; // DLT_USER0, treated as raw bytes by Wireshark
+
+/// Records every buffer written/read by a device to a capture file, so a
+/// mismatch reported by `Generator::validate` can be traced back to exactly
+/// where in the stream it happened.
+///
+/// The format is picked from the file extension: `.pcap` writes a pcap file
+/// (one record per captured buffer, link-type `DLT_USER0`) that can be opened
+/// directly in Wireshark; anything else gets a human-readable hex dump.
+pub struct Capture {
+    writer: BufWriter<File>,
+    pcap: bool,
+}
+
+impl Capture {
+    pub fn create(path: &str) -> Result<Capture> {
+        let pcap = Path::new(path)
+            .extension()
+            .map(|ext| ext == "pcap")
+            .unwrap_or(false);
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        if pcap {
+            // pcap global header, see https://wiki.wireshark.org/Development/LibpcapFileFormat
+            writer.write_all(&PCAP_MAGIC.to_le_bytes())?;
+            writer.write_all(&2u16.to_le_bytes())?; // version_major
+            writer.write_all(&4u16.to_le_bytes())?; // version_minor
+            writer.write_all(&0i32.to_le_bytes())?; // thiszone
+            writer.write_all(&0u32.to_le_bytes())?; // sigfigs
+            writer.write_all(&65535u32.to_le_bytes())?; // snaplen
+            writer.write_all(&PCAP_LINKTYPE_USER0.to_le_bytes())?; // network
+        }
+
+        Ok(Capture { writer, pcap })
+    }
+
+    pub fn log(&mut self, direction: Direction, data: &[u8]) -> Result<()> {
+        if self.pcap {
+            self.log_pcap(data)
+        } else {
+            self.log_hex(direction, data)
+        }
+    }
+
+    fn log_pcap(&mut self, data: &[u8]) -> Result<()> {
+        let elapsed = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        self.writer
+            .write_all(&(elapsed.as_secs() as u32).to_le_bytes())?; // ts_sec
+        self.writer
+            .write_all(&elapsed.subsec_micros().to_le_bytes())?; // ts_usec
+        self.writer.write_all(&(data.len() as u32).to_le_bytes())?; // incl_len
+        self.writer.write_all(&(data.len() as u32).to_le_bytes())?; // orig_len
+        self.writer.write_all(data)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn log_hex(&mut self, direction: Direction, data: &[u8]) -> Result<()> {
+        let elapsed = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        write!(
+            self.writer,
+            "[{}.{:06}] {} ({} bytes):",
+            elapsed.as_secs(),
+            elapsed.subsec_micros(),
+            direction.label(),
+            data.len()
+        )?;
+        for byte in data {
+            write!(self.writer, " {:02x}", byte)?;
+        }
+        writeln!(self.writer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use epoll::{ControlOptions, Event, Events};
+
+use crate::capture::{Capture, Direction};
+use crate::middleware::FaultInjector;
+use crate::Generator;
+
+const TOKEN_DEVICE: u64 = 0;
+const TOKEN_STOP: u64 = 1;
+
+/// A self-pipe used to interrupt a blocked `epoll_wait` immediately on
+/// shutdown, instead of waiting out a polling interval.
+pub struct StopPipe {
+    read_fd: RawFd,
+    write_fd: RawFd,
+}
+
+impl StopPipe {
+    pub fn create() -> Result<StopPipe> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(std::io::Error::last_os_error()).with_context(|| "Failed to create stop pipe");
+        }
+        Ok(StopPipe {
+            read_fd: fds[0],
+            write_fd: fds[1],
+        })
+    }
+
+    fn read_fd(&self) -> RawFd {
+        self.read_fd
+    }
+
+    /// Wakes up whichever event loop is blocked reading this pipe.
+    pub fn notify(&self) {
+        let byte = [1u8];
+        unsafe {
+            libc::write(self.write_fd, byte.as_ptr() as *const libc::c_void, 1);
+        }
+    }
+}
+
+impl Drop for StopPipe {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read_fd);
+            libc::close(self.write_fd);
+        }
+    }
+}
+
+/// Drives tx/rx for a single device on a readiness-based epoll loop: it
+/// blocks until the device fd is readable or writable (or the stop pipe
+/// fires) and then drains as much as possible, rather than capping
+/// throughput on a fixed polling cadence.
+pub fn run<T: Read + Write + AsRawFd>(
+    mut device: T,
+    tx_generator: Arc<Mutex<Generator>>,
+    rx_generator: Arc<Mutex<Generator>>,
+    capture: Option<Arc<Mutex<Capture>>>,
+    mut fault: Option<FaultInjector>,
+    stop: Arc<AtomicBool>,
+    stop_pipe: Arc<StopPipe>,
+) -> Result<()> {
+    let epfd = epoll::create(false).with_context(|| "Failed to create epoll instance")?;
+
+    epoll::ctl(
+        epfd,
+        ControlOptions::EPOLL_CTL_ADD,
+        device.as_raw_fd(),
+        Event::new(Events::EPOLLIN | Events::EPOLLOUT, TOKEN_DEVICE),
+    )
+    .with_context(|| "Failed to register device fd with epoll")?;
+    epoll::ctl(
+        epfd,
+        ControlOptions::EPOLL_CTL_ADD,
+        stop_pipe.read_fd(),
+        Event::new(Events::EPOLLIN, TOKEN_STOP),
+    )
+    .with_context(|| "Failed to register stop pipe with epoll")?;
+
+    let mut events = [Event::new(Events::empty(), 0); 2];
+    let mut bytes = 0;
+    let mut begin = SystemTime::now();
+
+    // A block that didn't fully land on the wire yet, and how much of it is
+    // already sent: a non-blocking fd routinely only accepts part of a write
+    // (a serial UART's hardware FIFO is a few bytes; a TCP send buffer fills
+    // under backpressure), so the remainder has to wait for the next
+    // EPOLLOUT instead of being treated as an error.
+    let mut pending_tx: Option<Vec<u8>> = None;
+    let mut tx_sent = 0;
+
+    'outer: loop {
+        let n = match epoll::wait(epfd, -1, &mut events) {
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).with_context(|| "epoll_wait failed"),
+        };
+
+        for event in &events[0..n] {
+            if event.data == TOKEN_STOP {
+                break 'outer;
+            }
+
+            if event.events & Events::EPOLLOUT.bits() != 0 {
+                loop {
+                    if pending_tx.is_none() {
+                        let data = tx_generator.lock().unwrap().generate();
+                        let data = match &mut fault {
+                            Some(fault) => fault.apply(&data),
+                            None => data,
+                        };
+                        if let Some(capture) = &capture {
+                            capture.lock().unwrap().log(Direction::Tx, &data).unwrap();
+                        }
+                        pending_tx = Some(data);
+                        tx_sent = 0;
+                    }
+
+                    let data = pending_tx.as_ref().unwrap();
+                    match device.write(&data[tx_sent..]) {
+                        Ok(sent) => {
+                            tx_sent += sent;
+                            if tx_sent == data.len() {
+                                pending_tx = None; // block flushed, generate the next one
+                            } else {
+                                break; // partial write, wait for the fd to be writable again
+                            }
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(e) => {
+                            println!("Tx error: {:?}", e);
+                            exit(1);
+                        }
+                    }
+                }
+            }
+
+            if event.events & Events::EPOLLIN.bits() != 0 {
+                let mut buf = [0u8; 2048]; // max 2k
+                if let Ok(n) = device.read(&mut buf) {
+                    if let Some(capture) = &capture {
+                        capture
+                            .lock()
+                            .unwrap()
+                            .log(Direction::Rx, &buf[0..n])
+                            .unwrap();
+                    }
+                    rx_generator.lock().unwrap().validate(&buf[0..n]).unwrap();
+                    bytes += n;
+                }
+            }
+        }
+
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+
+        if begin.elapsed().unwrap() >= Duration::from_secs(1) {
+            println!("transmission speed: {:?}KB/s", (bytes as f64) / 1000.0);
+            rx_generator.lock().unwrap().report_latency();
+            bytes = 0;
+            begin = SystemTime::now();
+        }
+    }
+
+    unsafe { libc::close(epfd) };
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+/// Accumulates latency samples (round-trip in echo mode, one-way in bridge
+/// mode) for latency mode (see
+/// `Generator::validate`), along with out-of-order/missing sequence counts,
+/// and reports a min/avg/max/p99 summary once a second alongside throughput.
+pub struct LatencyStats {
+    samples: Vec<Duration>,
+    next_expected_seq: u64,
+    out_of_order: u64,
+    missing: u64,
+}
+
+impl LatencyStats {
+    pub fn new() -> LatencyStats {
+        LatencyStats {
+            samples: Vec::new(),
+            next_expected_seq: 0,
+            out_of_order: 0,
+            missing: 0,
+        }
+    }
+
+    pub fn record(&mut self, seq: u64, latency: Duration) {
+        if seq == self.next_expected_seq {
+            self.next_expected_seq += 1;
+        } else if seq > self.next_expected_seq {
+            self.missing += seq - self.next_expected_seq;
+            self.next_expected_seq = seq + 1;
+        } else {
+            self.out_of_order += 1;
+        }
+        self.samples.push(latency);
+    }
+
+    pub fn report_and_reset(&mut self) {
+        if self.samples.is_empty() {
+            return;
+        }
+
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        let min = sorted[0];
+        let max = sorted[sorted.len() - 1];
+        let avg = sorted.iter().sum::<Duration>() / sorted.len() as u32;
+        let p99 = sorted[((sorted.len() - 1) * 99) / 100];
+
+        let jitter = if sorted.len() > 1 {
+            self.samples
+                .windows(2)
+                .map(|w| w[1].abs_diff(w[0]))
+                .sum::<Duration>()
+                / (self.samples.len() - 1) as u32
+        } else {
+            Duration::ZERO
+        };
+
+        println!(
+            "latency min/avg/max/p99: {:?}/{:?}/{:?}/{:?}, jitter: {:?}, out-of-order: {}, missing: {}",
+            min, avg, max, p99, jitter, self.out_of_order, self.missing
+        );
+
+        self.samples.clear();
+        self.out_of_order = 0;
+        self.missing = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_in_order_sequence_has_no_gaps() {
+        let mut stats = LatencyStats::new();
+        stats.record(0, Duration::from_millis(1));
+        stats.record(1, Duration::from_millis(1));
+        stats.record(2, Duration::from_millis(1));
+        assert_eq!(stats.out_of_order, 0);
+        assert_eq!(stats.missing, 0);
+    }
+
+    #[test]
+    fn test_gap_counts_as_missing() {
+        let mut stats = LatencyStats::new();
+        stats.record(0, Duration::from_millis(1));
+        stats.record(5, Duration::from_millis(1));
+        assert_eq!(stats.missing, 4);
+        assert_eq!(stats.next_expected_seq, 6);
+    }
+
+    #[test]
+    fn test_earlier_seq_counts_as_out_of_order() {
+        let mut stats = LatencyStats::new();
+        stats.record(2, Duration::from_millis(1));
+        stats.record(0, Duration::from_millis(1));
+        assert_eq!(stats.out_of_order, 1);
+    }
+
+    #[test]
+    fn test_report_and_reset_clears_counters_without_panicking_when_empty() {
+        let mut stats = LatencyStats::new();
+        stats.report_and_reset(); // no samples yet, must not panic
+        stats.record(0, Duration::from_millis(1));
+        stats.record(5, Duration::from_millis(1));
+        stats.report_and_reset();
+        assert_eq!(stats.out_of_order, 0);
+        assert_eq!(stats.missing, 0);
+    }
+}
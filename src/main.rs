@@ -2,7 +2,6 @@ use std::{
     any::type_name,
     collections::VecDeque,
     net::TcpStream,
-    process::exit,
     result::Result::Ok,
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -15,93 +14,240 @@ use std::{
 
 use anyhow::{anyhow, Context, Result};
 use clap::{Arg, Command};
+use serialport::SerialPort; // brings trait methods (e.g. set_timeout) into scope for TTYPort
 
+mod capture;
+use capture::Capture;
+
+mod middleware;
+use middleware::FaultConfig;
+
+mod eventloop;
+use eventloop::StopPipe;
+
+mod latency;
+use latency::LatencyStats;
+
+/// Generates and validates a deterministic pseudo-random byte stream so that
+/// transports which drop, duplicate, or corrupt bytes can't pass by accident,
+/// the way an all-zero payload would.
+///
+/// In latency mode, each 100-byte block's first 16 bytes are overwritten with
+/// a sequence number and a send timestamp, which `validate` extracts to feed
+/// `LatencyStats`; they are still compared byte-for-byte like the rest of the
+/// block.
 struct Generator {
     queue: VecDeque<u8>,
+    state: u32,
+    seq: u64,
+    start: time::Instant,
+    latency_stats: Option<LatencyStats>,
+    // Position within the current 100-byte block on the rx side, and the
+    // header bytes collected so far at that position: a read is never
+    // guaranteed to land on a block boundary, so both need to survive
+    // across `validate` calls rather than being recomputed per-call.
+    block_offset: usize,
+    header_buf: Vec<u8>,
 }
 
 impl Generator {
-    fn create() -> Result<Generator> {
+    fn create(seed: u32, latency_mode: bool) -> Result<Generator> {
         Ok(Generator {
             queue: VecDeque::new(),
+            // xorshift32 has a fixed point at 0, so nudge a zero seed away from it
+            state: if seed == 0 { 0x9e37_79b9 } else { seed },
+            seq: 0,
+            start: time::Instant::now(),
+            latency_stats: latency_mode.then(LatencyStats::new),
+            block_offset: 0,
+            header_buf: Vec::with_capacity(16),
         })
     }
 
+    fn next_byte(&mut self) -> u8 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        (x & 0xff) as u8
+    }
+
     fn generate(&mut self) -> Vec<u8> {
-        let data = vec![0_u8; 100];
+        let mut data = (0..100).map(|_| self.next_byte()).collect::<Vec<_>>();
+        if self.latency_stats.is_some() {
+            let seq = self.seq;
+            self.seq += 1;
+            let sent_at_nanos = self.start.elapsed().as_nanos() as u64;
+            data[0..8].copy_from_slice(&seq.to_le_bytes());
+            data[8..16].copy_from_slice(&sent_at_nanos.to_le_bytes());
+        }
         self.queue.extend(data.iter());
         data
     }
 
     fn validate(&mut self, data: &[u8]) -> Result<()> {
         let reference = self.queue.drain(0..data.len()).collect::<Vec<_>>();
+
+        if let Some(stats) = &mut self.latency_stats {
+            // `data.chunks(100)` would assume every read lands on a block
+            // boundary, which an event loop that drains as much as possible
+            // per readiness event never guarantees (it may coalesce several
+            // blocks into one read, or hand back a read that splits a block,
+            // or even splits the 16-byte header itself). Walk the buffer
+            // tracking our position within the current block instead, so
+            // the header is reassembled correctly regardless of how the
+            // bytes happened to be chunked on the way in.
+            let mut offset = 0;
+            while offset < data.len() {
+                let take = (100 - self.block_offset).min(data.len() - offset);
+                if self.block_offset < 16 {
+                    let header_take = (16 - self.block_offset).min(take);
+                    self.header_buf
+                        .extend_from_slice(&data[offset..offset + header_take]);
+                    if self.header_buf.len() == 16 {
+                        let seq = u64::from_le_bytes(self.header_buf[0..8].try_into().unwrap());
+                        let sent_at_nanos =
+                            u64::from_le_bytes(self.header_buf[8..16].try_into().unwrap());
+                        let sent_at = self.start + Duration::from_nanos(sent_at_nanos);
+                        stats.record(seq, sent_at.elapsed());
+                        self.header_buf.clear();
+                    }
+                }
+                self.block_offset += take;
+                offset += take;
+                if self.block_offset == 100 {
+                    self.block_offset = 0;
+                }
+            }
+        }
+
         if reference == data {
             Ok(())
         } else {
-            Err(anyhow!("value mismatch"))
+            let mismatches = reference
+                .iter()
+                .zip(data.iter())
+                .enumerate()
+                .filter(|(_, (r, d))| r != d)
+                .collect::<Vec<_>>();
+            let first_offset = mismatches.first().map(|(i, _)| *i).unwrap_or(0);
+            Err(anyhow!(
+                "value mismatch: {} of {} bytes wrong, first at offset {}",
+                mismatches.len(),
+                data.len(),
+                first_offset
+            ))
+        }
+    }
+
+    /// Prints the latency summary for the last reporting window, if latency
+    /// mode is enabled.
+    fn report_latency(&mut self) {
+        if let Some(stats) = &mut self.latency_stats {
+            stats.report_and_reset();
         }
     }
 }
 
 struct GenericDevice {
     threads: Vec<JoinHandle<()>>,
+    stop_pipe: Arc<StopPipe>,
 }
 
 impl GenericDevice {
-    fn create<T: std::io::Read + std::io::Write + std::marker::Send + 'static>(
-        mut tx_device: T,
-        mut rx_device: T,
+    fn create<
+        T: std::io::Read + std::io::Write + std::os::unix::io::AsRawFd + std::marker::Send + 'static,
+    >(
+        device: T,
         tx_generator: Arc<Mutex<Generator>>,
         rx_generator: Arc<Mutex<Generator>>,
+        capture: Option<Arc<Mutex<Capture>>>,
+        fault_config: FaultConfig,
         stop: Arc<AtomicBool>,
     ) -> Result<GenericDevice> {
-        let stop_tx = stop.clone();
-        let stop_rx = stop.clone();
-        let mut threads = Vec::new();
-
-        // tx
-        threads.push(thread::spawn(move || {
-            println!("starts tx with device type {}", type_name::<T>());
-            loop {
-                let data = tx_generator.lock().unwrap().generate();
-                tx_device.write_all(&data).unwrap_or_else(|e| {
-                    println!("Tx error: {:?}", e);
-                    exit(1);
-                });
-                if stop_tx.load(Ordering::SeqCst) {
-                    break;
-                }
-                thread::sleep(Duration::from_millis(1))
-            }
-            println!("stops tx with device type {}", type_name::<T>());
-        }));
-
-        // rx
-
-        threads.push(thread::spawn(move || {
-            let mut bytes = 0;
-            let mut begin = time::SystemTime::now();
-            let mut buf = [0u8; 2048]; // max 2k
-            println!("starts rx with device type {}", type_name::<T>());
-            loop {
-                if let Ok(n) = rx_device.read(&mut buf) {
-                    rx_generator.lock().unwrap().validate(&buf[0..n]).unwrap();
-                    bytes = bytes + n;
-                }
-                if stop_rx.load(Ordering::SeqCst) {
-                    break;
-                }
-                if begin.elapsed().unwrap() >= time::Duration::from_secs(1) {
-                    println!("transmission speed: {:?}KB/s", (bytes as f64) / 1000.0);
-                    bytes = 0;
-                    begin = time::SystemTime::now();
-                }
-                thread::sleep(time::Duration::from_millis(1));
-            }
-            println!("stops rx with device type {}", type_name::<T>());
-        }));
+        let stop_pipe = Arc::new(StopPipe::create()?);
+        let thread_stop_pipe = stop_pipe.clone();
+
+        let thread = thread::spawn(move || {
+            println!("starts device type {}", type_name::<T>());
+            // Built here, one fresh instance per device [see FaultConfig].
+            let fault = fault_config.build();
+            eventloop::run(
+                device,
+                tx_generator,
+                rx_generator,
+                capture,
+                fault,
+                stop,
+                thread_stop_pipe,
+            )
+            .unwrap();
+            println!("stops device type {}", type_name::<T>());
+        });
+
+        Ok(GenericDevice {
+            threads: vec![thread],
+            stop_pipe,
+        })
+    }
+
+    /// Wakes the event loop so shutdown happens immediately instead of
+    /// waiting for the next readiness event.
+    fn shutdown(self) {
+        self.stop_pipe.notify();
+        for t in self.threads {
+            t.join().unwrap();
+        }
+    }
+}
+
+/// Owns the two devices of a bridge run and the pair of generators that cross
+/// between them: device A's tx is validated by device B's rx and vice-versa,
+/// so each direction gets its own `Generator` instance shared by both devices.
+struct Controller {
+    devices: Vec<GenericDevice>,
+}
+
+impl Controller {
+    fn create(
+        config_a: &str,
+        config_b: &str,
+        seed: u32,
+        latency_mode: bool,
+        capture: Option<Arc<Mutex<Capture>>>,
+        fault_config: FaultConfig,
+        stop: Arc<AtomicBool>,
+    ) -> Result<Controller> {
+        // each direction gets its own seed so the two streams are distinguishable
+        let a_to_b_generator = Arc::new(Mutex::new(Generator::create(seed, latency_mode)?));
+        let b_to_a_generator = Arc::new(Mutex::new(Generator::create(
+            seed.wrapping_add(1),
+            latency_mode,
+        )?));
+
+        // `fault_config` is Copy; each device builds its own injector from
+        // it [see FaultConfig].
+        let device_a = create_device(
+            config_a,
+            a_to_b_generator.clone(),
+            b_to_a_generator.clone(),
+            capture.clone(),
+            fault_config,
+            stop.clone(),
+        )?;
+        let device_b = create_device(
+            config_b,
+            b_to_a_generator,
+            a_to_b_generator,
+            capture,
+            fault_config,
+            stop,
+        )?;
 
-        Ok(GenericDevice { threads })
+        Ok(Controller {
+            devices: vec![device_a, device_b],
+        })
     }
 }
 
@@ -109,48 +255,123 @@ fn create_tcp_device(
     config: &str,
     tx_generator: Arc<Mutex<Generator>>,
     rx_generator: Arc<Mutex<Generator>>,
+    capture: Option<Arc<Mutex<Capture>>>,
+    fault_config: FaultConfig,
     stop: Arc<AtomicBool>,
 ) -> Result<GenericDevice> {
     let tcp = TcpStream::connect(config)
         .with_context(|| format!("Failed to connect to remote_ip {}", config))?;
     tcp.set_nodelay(true)?; // turn off write package grouping, send out tcp package as-is
-    tcp.set_write_timeout(Some(time::Duration::from_secs(10)))?; // non-blocking write
-    tcp.set_read_timeout(Some(time::Duration::from_millis(10)))?; // non-blocking read
+    tcp.set_nonblocking(true)?; // reads/writes are only attempted once epoll reports the fd ready
 
     Ok(GenericDevice::create(
-        tcp.try_clone()?,
-        tcp.try_clone()?,
+        tcp,
         tx_generator,
         rx_generator,
+        capture,
+        fault_config,
         stop,
     )?)
 }
 
+/// Parses a 3-character word-length/parity/stop-bits word such as "8N1" or
+/// "7E1", mirroring the line-control fields of a 16550 UART.
+fn parse_line_format(
+    format: &str,
+) -> Result<(serialport::DataBits, serialport::Parity, serialport::StopBits)> {
+    let chars = format.chars().collect::<Vec<_>>();
+    if chars.len() != 3 {
+        return Err(anyhow!(
+            "invalid line format {:?}, expected e.g. \"8N1\"",
+            format
+        ));
+    }
+
+    let data_bits = match chars[0] {
+        '5' => serialport::DataBits::Five,
+        '6' => serialport::DataBits::Six,
+        '7' => serialport::DataBits::Seven,
+        '8' => serialport::DataBits::Eight,
+        other => return Err(anyhow!("invalid data bits {:?}, expected 5-8", other)),
+    };
+    let parity = match chars[1].to_ascii_uppercase() {
+        'N' => serialport::Parity::None,
+        'E' => serialport::Parity::Even,
+        'O' => serialport::Parity::Odd,
+        other => return Err(anyhow!("invalid parity {:?}, expected N, E, or O", other)),
+    };
+    let stop_bits = match chars[2] {
+        '1' => serialport::StopBits::One,
+        '2' => serialport::StopBits::Two,
+        other => return Err(anyhow!("invalid stop bits {:?}, expected 1 or 2", other)),
+    };
+
+    Ok((data_bits, parity, stop_bits))
+}
+
+fn parse_flow_control(flow_control: &str) -> Result<serialport::FlowControl> {
+    match flow_control.to_ascii_lowercase().as_str() {
+        "none" => Ok(serialport::FlowControl::None),
+        "rtscts" => Ok(serialport::FlowControl::Hardware),
+        "xonxoff" => Ok(serialport::FlowControl::Software),
+        other => Err(anyhow!(
+            "invalid flow control {:?}, expected none, rtscts, or xonxoff",
+            other
+        )),
+    }
+}
+
 fn create_serial_device(
     config: &str,
     tx_generator: Arc<Mutex<Generator>>,
     rx_generator: Arc<Mutex<Generator>>,
+    capture: Option<Arc<Mutex<Capture>>>,
+    fault_config: FaultConfig,
     stop: Arc<AtomicBool>,
 ) -> Result<GenericDevice> {
     let mut serial_iter = config.split(':');
     let device = serial_iter.next().unwrap();
     let baud_rate = serial_iter.next().unwrap().parse::<u32>().unwrap();
+    let (data_bits, parity, stop_bits) = serial_iter
+        .next()
+        .map(parse_line_format)
+        .transpose()?
+        .unwrap_or((
+            serialport::DataBits::Eight,
+            serialport::Parity::None,
+            serialport::StopBits::One,
+        ));
+    let flow_control = serial_iter
+        .next()
+        .map(parse_flow_control)
+        .transpose()?
+        .unwrap_or(serialport::FlowControl::None);
 
-    let mut serialport = serialport::new(device, baud_rate).open().with_context(|| {
-        format!(
-            "Failed to open serialport device {} with baud rate {}",
-            device, baud_rate
-        )
-    })?;
-    serialport
-        .set_timeout(time::Duration::from_secs(1))
-        .unwrap();
+    // `.open()` returns a boxed `dyn SerialPort`, which isn't `Sized` and
+    // doesn't implement `AsRawFd`; `.open_native()` gives back the concrete
+    // platform port (`TTYPort` on Unix) that `GenericDevice::create` needs.
+    let mut serialport = serialport::new(device, baud_rate)
+        .data_bits(data_bits)
+        .parity(parity)
+        .stop_bits(stop_bits)
+        .flow_control(flow_control)
+        .open_native()
+        .with_context(|| {
+            format!(
+                "Failed to open serialport device {} with baud rate {}",
+                device, baud_rate
+            )
+        })?;
+    // epoll already tells us when the fd is ready, so reads/writes should
+    // return immediately rather than block waiting for more data
+    serialport.set_timeout(time::Duration::from_millis(0)).unwrap();
 
     Ok(GenericDevice::create(
-        serialport.try_clone()?,
-        serialport.try_clone()?,
+        serialport,
         tx_generator,
         rx_generator,
+        capture,
+        fault_config,
         stop,
     )?)
 }
@@ -159,12 +380,28 @@ fn create_device(
     config: &str,
     tx_generator: Arc<Mutex<Generator>>,
     rx_generator: Arc<Mutex<Generator>>,
+    capture: Option<Arc<Mutex<Capture>>>,
+    fault_config: FaultConfig,
     stop: Arc<AtomicBool>,
 ) -> Result<GenericDevice> {
     if config.starts_with("tcp:") {
-        create_tcp_device(&config[4..], tx_generator, rx_generator, stop)
+        create_tcp_device(
+            &config[4..],
+            tx_generator,
+            rx_generator,
+            capture,
+            fault_config,
+            stop,
+        )
     } else if config.starts_with("serial:") {
-        create_serial_device(&config[7..], tx_generator, rx_generator, stop)
+        create_serial_device(
+            &config[7..],
+            tx_generator,
+            rx_generator,
+            capture,
+            fault_config,
+            stop,
+        )
     } else {
         panic!("unsupported device {:?}", config)
     }
@@ -180,10 +417,60 @@ fn main() -> Result<()> {
                 .short('d').long("device")
                 .value_names(["TYPE:DEVICE", "TYPE:DEVICE or echo"])
                 .num_args(2)
-                .help("Serial port: serial:/dev/ttyUSB0:115200 (Linux) or serial:COM1:115200 (Windows),\n\
+                .help("Serial port: serial:/dev/ttyUSB0:115200[:8N1[:rtscts|xonxoff]] (Unix only),\n\
+                       defaults to 8N1 with no flow control,\n\
                        TCP: tcp:192.168.7.1:8000 for tcp server\n\
                        Echo mode: use \"echo\" in place of the second device"),
         )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("SEED")
+                .default_value("1")
+                .help("Seed for the pseudo-random payload generator, for reproducible runs"),
+        )
+        .arg(
+            Arg::new("capture")
+                .long("capture")
+                .value_name("PATH")
+                .help("Record every transmitted/received buffer to PATH; \".pcap\" writes a pcap \
+                       capture openable in Wireshark, any other extension writes a hex dump"),
+        )
+        .arg(
+            Arg::new("drop-chance")
+                .long("drop-chance")
+                .value_name("0..1")
+                .default_value("0")
+                .help("Probability of discarding each transmitted byte, for robustness testing"),
+        )
+        .arg(
+            Arg::new("corrupt-chance")
+                .long("corrupt-chance")
+                .value_name("0..1")
+                .default_value("0")
+                .help("Probability of flipping a random bit in each transmitted byte"),
+        )
+        .arg(
+            Arg::new("shaping-rate")
+                .long("shaping-rate")
+                .value_name("BYTES_PER_SEC")
+                .help("Cap throughput to this many bytes/sec with a token-bucket rate limiter"),
+        )
+        .arg(
+            Arg::new("extra-latency")
+                .long("extra-latency")
+                .value_name("MILLISECONDS")
+                .default_value("0")
+                .help("Extra delay injected before each write, to simulate a slow link"),
+        )
+        .arg(
+            Arg::new("latency")
+                .long("latency")
+                .num_args(0)
+                .help("Embed a sequence number and send timestamp in each block and report \
+                       min/avg/max/p99 latency and jitter alongside throughput (round-trip \
+                       in echo mode, one-way from device A to B and B to A in bridge mode)"),
+        )
         .get_matches();
 
     let configs = m
@@ -193,7 +480,44 @@ fn main() -> Result<()> {
         .collect::<Vec<_>>();
     assert_eq!(configs.len(), 2);
 
-    let generator = Arc::new(Mutex::new(Generator::create()?));
+    let seed = m
+        .get_one::<String>("seed")
+        .unwrap()
+        .parse::<u32>()
+        .with_context(|| "Failed to parse --seed as a u32")?;
+
+    let latency_mode = m.get_flag("latency");
+
+    let generator = Arc::new(Mutex::new(Generator::create(seed, latency_mode)?));
+
+    let capture = m
+        .get_one::<String>("capture")
+        .map(|path| -> Result<_> { Ok(Arc::new(Mutex::new(Capture::create(path)?))) })
+        .transpose()?;
+
+    let drop_chance = m.get_one::<String>("drop-chance").unwrap().parse::<f64>()?;
+    let corrupt_chance = m
+        .get_one::<String>("corrupt-chance")
+        .unwrap()
+        .parse::<f64>()?;
+    let shaping_rate = m
+        .get_one::<String>("shaping-rate")
+        .map(|v| v.parse::<u64>())
+        .transpose()?;
+    if shaping_rate == Some(0) {
+        return Err(anyhow!(
+            "--shaping-rate must be greater than 0 (a 0 bytes/sec rate can never let any data through)"
+        ));
+    }
+    let extra_latency =
+        Duration::from_millis(m.get_one::<String>("extra-latency").unwrap().parse()?);
+
+    let fault_config = FaultConfig {
+        drop_chance,
+        corrupt_chance,
+        extra_latency,
+        shaping_rate,
+    };
 
     let stop = Arc::new(AtomicBool::new(false));
     let mut device_vec: Vec<GenericDevice> = Vec::new();
@@ -203,22 +527,24 @@ fn main() -> Result<()> {
             configs[0],
             generator.clone(),
             generator.clone(),
+            capture.clone(),
+            fault_config,
             stop.clone(),
         )?);
     } else {
-        // device_vec.push(create_device(configs[0], stop.clone())?);
-        // device_vec.push(create_device(configs[1], stop.clone())?);
-        // controller_vec.push(Controller::create(
-        //     device_vec[0].tx.clone(),
-        //     device_vec[1].rx.clone(),
-        //     stop.clone(),
-        // )?);
-        // controller_vec.push(Controller::create(
-        //     device_vec[1].tx.clone(),
-        //     device_vec[0].rx.clone(),
-        //     stop.clone(),
-        // )?);
-        unimplemented!()
+        // two-device bridge mode: device A's tx is validated against by device B's rx
+        // and vice-versa, so each direction needs its own generator instance shared
+        // across both devices
+        let controller = Controller::create(
+            configs[0],
+            configs[1],
+            seed,
+            latency_mode,
+            capture,
+            fault_config,
+            stop.clone(),
+        )?;
+        device_vec.extend(controller.devices);
     }
 
     // wait for ctrl-c
@@ -230,11 +556,7 @@ fn main() -> Result<()> {
     println!("Goodbye!");
 
     stop.store(true, Ordering::SeqCst);
-    device_vec.iter_mut().for_each(|d: &mut GenericDevice| {
-        while let Some(t) = d.threads.pop() {
-            t.join().unwrap();
-        }
-    });
+    device_vec.into_iter().for_each(GenericDevice::shutdown);
 
     Ok(())
 }
@@ -248,25 +570,56 @@ mod test {
         thread,
     };
 
-    use crate::{create_serial_device, create_tcp_device, Generator};
+    use crate::{create_serial_device, create_tcp_device, Controller, Generator};
+    use crate::middleware::FaultConfig;
 
     #[test]
     fn test_generator() {
-        let mut generate = Generator::create().unwrap();
+        let mut generate = Generator::create(1, false).unwrap();
         let data = generate.generate();
         assert!(generate.validate(&data).is_ok());
     }
 
+    #[test]
+    fn test_generator_latency_header_split_across_reads() {
+        // The event loop drains as much as a readiness event gives it, so a
+        // single validate() call can receive anywhere from a few bytes of a
+        // block to several whole blocks. Feed it a handful of blocks broken
+        // into arbitrary, non-block-aligned chunks and make sure every chunk
+        // validates and the header tracking ends back on a block boundary.
+        let mut generate = Generator::create(1, true).unwrap();
+        let mut data = Vec::new();
+        for _ in 0..3 {
+            data.extend(generate.generate());
+        }
+
+        let chunk_sizes: [usize; 6] = [5, 20, 11, 64, 100, 100];
+        for chunk in chunk_sizes {
+            let chunk = chunk.min(data.len());
+            let (head, rest) = data.split_at(chunk);
+            assert!(generate.validate(head).is_ok());
+            data = rest.to_vec();
+            if data.is_empty() {
+                break;
+            }
+        }
+        assert!(data.is_empty());
+        assert_eq!(generate.block_offset, 0);
+        assert!(generate.header_buf.is_empty());
+    }
+
     #[test]
     fn test_serial_device() {
         let stop = Arc::new(AtomicBool::new(false));
-        let generator = Arc::new(Mutex::new(Generator::create().unwrap()));
+        let generator = Arc::new(Mutex::new(Generator::create(1, false).unwrap()));
 
         // test with serial echo server at /tmp/serial0
         let dev = create_serial_device(
             "/tmp/serial0:115200",
             generator.clone(),
             generator.clone(),
+            None,
+            FaultConfig::none(),
             stop.clone(),
         )
         .unwrap();
@@ -276,16 +629,37 @@ mod test {
     #[test]
     fn test_tcp_device() {
         let stop = Arc::new(AtomicBool::new(false));
-        let generator = Arc::new(Mutex::new(Generator::create().unwrap()));
+        let generator = Arc::new(Mutex::new(Generator::create(1, false).unwrap()));
 
         // test with TCP echo server at port 4000
         let dev = create_tcp_device(
             "127.0.0.1:4000",
             generator.clone(),
             generator.clone(),
+            None,
+            FaultConfig::none(),
+            stop.clone(),
+        )
+        .unwrap();
+        thread::sleep(time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_controller_bridge() {
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // test with TCP ports 4000 and 4001 forwarding to each other
+        let controller = Controller::create(
+            "tcp:127.0.0.1:4000",
+            "tcp:127.0.0.1:4001",
+            1,
+            false,
+            None,
+            FaultConfig::none(),
             stop.clone(),
         )
         .unwrap();
         thread::sleep(time::Duration::from_secs(1));
+        assert_eq!(controller.devices.len(), 2);
     }
 }